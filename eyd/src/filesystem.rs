@@ -0,0 +1,316 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The kind of thing a [`Filesystem`] found at a path. `Symlink` is only
+/// reported by [`Filesystem::symlink_metadata`]; [`Filesystem::metadata`]
+/// follows the link and reports what it points at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FileMetadata {
+    pub kind: FileKind,
+    pub mode: u32,
+}
+
+impl FileMetadata {
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileKind::Dir
+    }
+}
+
+/// The filesystem operations `eyd`'s walk, moves and cleanup perform,
+/// abstracted so callers can run against the real disk, an in-memory fake
+/// (for tests), or a logging-only fake (for `--dry-run`). Resolving keep
+/// entries (`canonicalize`, same-file checks) still goes to the real disk.
+pub trait Filesystem: Send + Sync {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>>;
+    /// Follows symlinks, like `std::fs::metadata`.
+    fn metadata(&self, path: &Path) -> Result<FileMetadata>;
+    /// Does not follow the final component, like `std::fs::symlink_metadata`.
+    fn symlink_metadata(&self, path: &Path) -> Result<FileMetadata>;
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+    fn create_dir(&self, path: &Path, mode: u32) -> Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> Result<()>;
+    fn exists(&self, path: &Path) -> bool;
+}
+
+/// The real thing: every call forwards straight to `std::fs`.
+pub struct StdFilesystem;
+
+impl Filesystem for StdFilesystem {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        Ok(fs::read_dir(path)?.flatten().map(|entry| entry.path()).collect())
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        let metadata = fs::metadata(path)?;
+        Ok(FileMetadata {
+            kind: if metadata.is_dir() {
+                FileKind::Dir
+            } else {
+                FileKind::File
+            },
+            mode: metadata.permissions().mode(),
+        })
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<FileMetadata> {
+        let metadata = fs::symlink_metadata(path)?;
+        let kind = if metadata.file_type().is_symlink() {
+            FileKind::Symlink
+        } else if metadata.is_dir() {
+            FileKind::Dir
+        } else {
+            FileKind::File
+        };
+        Ok(FileMetadata {
+            kind,
+            mode: metadata.permissions().mode(),
+        })
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn create_dir(&self, path: &Path, mode: u32) -> Result<()> {
+        fs::DirBuilder::new().mode(mode).create(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+}
+
+/// Wraps another [`Filesystem`] and turns every mutating call into a
+/// `println!` describing what would have happened, for `--dry-run`. Reads
+/// still go to `inner` so the walk sees a realistic tree to preview.
+pub struct DryRunFilesystem<'a> {
+    pub inner: &'a dyn Filesystem,
+}
+
+impl Filesystem for DryRunFilesystem<'_> {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        self.inner.read_dir(path)
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<FileMetadata> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        println!(
+            "[dry-run] would move {} -> {}",
+            from.display(),
+            to.display()
+        );
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path, mode: u32) -> Result<()> {
+        println!(
+            "[dry-run] would create directory {} with mode {:#o}",
+            path.display(),
+            mode
+        );
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        println!("[dry-run] would remove {}", path.display());
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+}
+
+/// An in-memory [`Filesystem`] for deterministic tests, modeled on Zed's
+/// `FakeFs`: a flat map from path to metadata, with directory renames and
+/// removals rewriting/dropping every path under the prefix.
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeFs {
+    entries: Mutex<BTreeMap<PathBuf, FileMetadata>>,
+}
+
+#[cfg(test)]
+impl FakeFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert_dir(&self, path: impl Into<PathBuf>, mode: u32) {
+        self.entries.lock().unwrap().insert(
+            path.into(),
+            FileMetadata {
+                kind: FileKind::Dir,
+                mode,
+            },
+        );
+    }
+
+    pub fn insert_file(&self, path: impl Into<PathBuf>, mode: u32) {
+        self.entries.lock().unwrap().insert(
+            path.into(),
+            FileMetadata {
+                kind: FileKind::File,
+                mode,
+            },
+        );
+    }
+
+    pub fn insert_symlink(&self, path: impl Into<PathBuf>) {
+        self.entries.lock().unwrap().insert(
+            path.into(),
+            FileMetadata {
+                kind: FileKind::Symlink,
+                mode: 0o777,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+impl Filesystem for FakeFs {
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let entries = self.entries.lock().unwrap();
+        if path != Path::new("/") && !entries.contains_key(path) {
+            return Err(Error::new(ErrorKind::NotFound, "no such directory"));
+        }
+        Ok(entries
+            .keys()
+            .filter(|candidate| candidate.parent() == Some(path))
+            .cloned()
+            .collect())
+    }
+
+    fn metadata(&self, path: &Path) -> Result<FileMetadata> {
+        // `FakeFs` doesn't model symlink targets, so there's nothing further
+        // to follow here; this is just an alias for `symlink_metadata`.
+        self.symlink_metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> Result<FileMetadata> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(path)
+            .copied()
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "no such file or directory"))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let moved = entries
+            .keys()
+            .filter(|candidate| *candidate == from || candidate.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        if moved.is_empty() {
+            return Err(Error::new(ErrorKind::NotFound, "no such file or directory"));
+        }
+        for path in moved {
+            let metadata = entries.remove(&path).unwrap();
+            let rest = path.strip_prefix(from).unwrap_or(Path::new(""));
+            entries.insert(to.join(rest), metadata);
+        }
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path, mode: u32) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.contains_key(path) {
+            return Err(Error::new(ErrorKind::AlreadyExists, "already exists"));
+        }
+        entries.insert(
+            path.into(),
+            FileMetadata {
+                kind: FileKind::Dir,
+                mode,
+            },
+        );
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|candidate, _| candidate != path && !candidate.starts_with(path));
+        if entries.len() == before {
+            return Err(Error::new(ErrorKind::NotFound, "no such file or directory"));
+        }
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.entries.lock().unwrap().contains_key(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_fs_rename_moves_subtree() {
+        let fake = FakeFs::new();
+        fake.insert_dir("/var", 0o755);
+        fake.insert_dir("/var/lib", 0o755);
+        fake.insert_file("/var/lib/cert", 0o644);
+
+        fake.rename(Path::new("/var"), Path::new("/oldroot/var")).unwrap();
+
+        assert!(!fake.exists(Path::new("/var")));
+        assert!(fake.exists(Path::new("/oldroot/var")));
+        assert!(fake.exists(Path::new("/oldroot/var/lib")));
+        assert!(fake.exists(Path::new("/oldroot/var/lib/cert")));
+    }
+
+    #[test]
+    fn test_fake_fs_create_dir_rejects_duplicate() {
+        let fake = FakeFs::new();
+        fake.create_dir(Path::new("/oldroot"), 0o755).unwrap();
+        assert_eq!(
+            fake.create_dir(Path::new("/oldroot"), 0o755)
+                .unwrap_err()
+                .kind(),
+            ErrorKind::AlreadyExists
+        );
+    }
+
+    #[test]
+    fn test_dry_run_does_not_mutate() {
+        let fake = FakeFs::new();
+        fake.insert_dir("/var", 0o755);
+        let dry_run = DryRunFilesystem { inner: &fake };
+
+        dry_run
+            .rename(Path::new("/var"), Path::new("/oldroot/var"))
+            .unwrap();
+        dry_run.create_dir(Path::new("/new"), 0o755).unwrap();
+        dry_run.remove_dir_all(Path::new("/var")).unwrap();
+
+        assert!(fake.exists(Path::new("/var")));
+        assert!(!fake.exists(Path::new("/oldroot/var")));
+        assert!(!fake.exists(Path::new("/new")));
+    }
+}
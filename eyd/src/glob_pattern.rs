@@ -0,0 +1,180 @@
+use std::path::{Path, PathBuf};
+
+/// One component of a compiled [`GlobPattern`]. `pub(crate)` so
+/// `keep_trie` can build a trie over these without going back through glob
+/// syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Segment {
+    Literal(String),
+    Wildcard(glob::Pattern),
+    /// A bare `**` component: matches this entry and everything beneath it,
+    /// however deep. Only meaningful as the final segment.
+    DoubleStar,
+}
+
+impl Segment {
+    fn new(raw: &str) -> Segment {
+        if raw == "**" {
+            Segment::DoubleStar
+        } else if raw.contains(['*', '?', '[']) {
+            match glob::Pattern::new(raw) {
+                Ok(pattern) => Segment::Wildcard(pattern),
+                Err(_) => Segment::Literal(raw.to_string()),
+            }
+        } else {
+            Segment::Literal(raw.to_string())
+        }
+    }
+
+    pub(crate) fn matches(&self, component: &str) -> bool {
+        match self {
+            Segment::Literal(literal) => literal == component,
+            Segment::Wildcard(pattern) => pattern.matches(component),
+            Segment::DoubleStar => true,
+        }
+    }
+}
+
+/// A shell-style glob pattern over a whole path, matched one path component
+/// at a time (a wildcard segment like `ssh_host_*_key` never crosses a `/`).
+/// A `**` segment matches the rest of the path, however deep.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobPattern {
+    segments: Vec<Segment>,
+}
+
+impl GlobPattern {
+    /// The pattern's compiled components, for building a [`crate::keep_trie::KeepTrie`].
+    pub(crate) fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Canonical string form of the pattern, used for ordering so
+    /// `GlobPattern`s can live in a `BTreeSet` like the plain paths they
+    /// replace.
+    fn key(&self) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => literal.clone(),
+                Segment::Wildcard(pattern) => pattern.as_str().to_string(),
+                Segment::DoubleStar => "**".to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+impl PartialOrd for GlobPattern {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for GlobPattern {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl GlobPattern {
+    pub fn new(path: &Path) -> GlobPattern {
+        let segments = path
+            .components()
+            .map(|component| Segment::new(&component.as_os_str().to_string_lossy()))
+            .collect();
+        GlobPattern { segments }
+    }
+
+    /// Whether every path this pattern matches also falls under `other`
+    /// (i.e. keeping `other` makes this pattern redundant). Used by
+    /// `normalize_keep` to drop keep entries already covered by another.
+    pub fn covered_by(&self, other: &GlobPattern) -> bool {
+        fn covers(segments: &[Segment], entry: &[Segment]) -> bool {
+            match segments.split_first() {
+                None => true,
+                Some((Segment::DoubleStar, _)) => true,
+                Some((segment, rest)) => match entry.split_first() {
+                    Some((Segment::Literal(literal), rest_entry)) => {
+                        segment.matches(literal) && covers(rest, rest_entry)
+                    }
+                    // A wildcard in `self` is only known to be covered by the
+                    // same wildcard or by a bare `*`.
+                    Some((Segment::Wildcard(pattern), rest_entry)) => {
+                        matches!(segment, Segment::Wildcard(wildcard)
+                            if wildcard.as_str() == pattern.as_str() || wildcard.as_str() == "*")
+                            && covers(rest, rest_entry)
+                    }
+                    Some((Segment::DoubleStar, _)) | None => false,
+                },
+            }
+        }
+
+        // Ask whether `other` fully matches `self` or an ancestor of it.
+        covers(&other.segments, &self.segments)
+    }
+
+    /// The literal path this pattern matches, if it contains no glob
+    /// segments (`*`, `?`, `[...]`, or `**`). Used by `normalize_keep`'s
+    /// same-file de-duplication, which needs a real path to stat.
+    pub(crate) fn as_literal_path(&self) -> Option<PathBuf> {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => Some(literal.as_str()),
+                Segment::Wildcard(_) | Segment::DoubleStar => None,
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|parts| parts.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments_classify_literal_wildcard_and_double_star() {
+        // The root component (`/`) becomes its own leading literal segment.
+        let literal = GlobPattern::new(Path::new("/var/log"));
+        assert!(matches!(
+            literal.segments(),
+            [Segment::Literal(root), Segment::Literal(a), Segment::Literal(b)]
+            if root == "/" && a == "var" && b == "log"
+        ));
+
+        let wildcard = GlobPattern::new(Path::new("/etc/ssh/ssh_host_*_key"));
+        assert!(matches!(
+            wildcard.segments(),
+            [
+                Segment::Literal(_),
+                Segment::Literal(_),
+                Segment::Literal(_),
+                Segment::Wildcard(_)
+            ]
+        ));
+        let Segment::Wildcard(pattern) = &wildcard.segments()[3] else {
+            panic!("expected a wildcard segment");
+        };
+        assert!(pattern.matches("ssh_host_ed25519_key"));
+        assert!(!pattern.matches("ssh_host_ed25519_key.pub"));
+
+        let recursive = GlobPattern::new(Path::new("/nix/**"));
+        assert!(matches!(
+            recursive.segments(),
+            [Segment::Literal(_), Segment::Literal(_), Segment::DoubleStar]
+        ));
+    }
+
+    #[test]
+    fn test_covered_by() {
+        let parent = GlobPattern::new(Path::new("/var"));
+        let child = GlobPattern::new(Path::new("/var/log"));
+        assert!(child.covered_by(&parent));
+        assert!(!parent.covered_by(&child));
+
+        let wildcard_parent = GlobPattern::new(Path::new("/var/lib/*"));
+        let child2 = GlobPattern::new(Path::new("/var/lib/docker/state"));
+        assert!(child2.covered_by(&wildcard_parent));
+    }
+}
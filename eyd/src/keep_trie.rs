@@ -0,0 +1,200 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Component, Path};
+
+use crate::glob_pattern::{GlobPattern, Segment};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WalkAction {
+    Skip,
+    Recurse,
+    Yield,
+}
+
+/// Combine two classifications of the same entry reached via different
+/// matching keep patterns, preferring the more conservative (more "kept")
+/// outcome: `Skip` beats `Recurse` beats `Yield`.
+fn combine(a: WalkAction, b: WalkAction) -> WalkAction {
+    match (a, b) {
+        (WalkAction::Skip, _) | (_, WalkAction::Skip) => WalkAction::Skip,
+        (WalkAction::Recurse, _) | (_, WalkAction::Recurse) => WalkAction::Recurse,
+        _ => WalkAction::Yield,
+    }
+}
+
+/// A node in the [`KeepTrie`], one per path component. Literal components
+/// are keyed by a `HashMap` for O(1) lookup; wildcard components can't be
+/// hashed like that, so they fall back to a short linear scan.
+#[derive(Default)]
+struct TrieNode {
+    /// A keep pattern ends exactly here.
+    is_leaf: bool,
+    /// A `**` keep pattern ends here: everything beneath this point is kept.
+    recursive: bool,
+    literal_children: HashMap<String, TrieNode>,
+    wildcard_children: Vec<(glob::Pattern, TrieNode)>,
+}
+
+impl TrieNode {
+    fn insert(&mut self, segments: &[Segment]) {
+        let Some((segment, rest)) = segments.split_first() else {
+            self.is_leaf = true;
+            return;
+        };
+
+        match segment {
+            Segment::DoubleStar => self.recursive = true,
+            Segment::Literal(literal) => {
+                self.literal_children
+                    .entry(literal.clone())
+                    .or_default()
+                    .insert(rest);
+            }
+            Segment::Wildcard(pattern) => {
+                let child = match self
+                    .wildcard_children
+                    .iter_mut()
+                    .find(|(existing, _)| existing.as_str() == pattern.as_str())
+                {
+                    Some((_, child)) => child,
+                    None => {
+                        self.wildcard_children.push((pattern.clone(), TrieNode::default()));
+                        &mut self.wildcard_children.last_mut().unwrap().1
+                    }
+                };
+                child.insert(rest);
+            }
+        }
+    }
+
+    fn has_children(&self) -> bool {
+        !self.literal_children.is_empty() || !self.wildcard_children.is_empty()
+    }
+
+    /// Classify `components` against this node, trying every child that
+    /// matches the next component (the literal child, if any, *and* every
+    /// wildcard child whose pattern matches) rather than stopping at the
+    /// first match. A literal keep entry and a wildcard keep entry sharing a
+    /// path prefix both need a say in the result, so a component can't just
+    /// pick one branch and discard the rest.
+    fn classify(&self, components: &[Component]) -> WalkAction {
+        if self.recursive {
+            return WalkAction::Skip;
+        }
+
+        let Some((component, rest)) = components.split_first() else {
+            return if self.is_leaf {
+                WalkAction::Skip
+            } else if self.has_children() {
+                WalkAction::Recurse
+            } else {
+                WalkAction::Yield
+            };
+        };
+        let component = component.as_os_str().to_string_lossy();
+
+        let mut result = WalkAction::Yield;
+        if let Some(child) = self.literal_children.get(component.as_ref()) {
+            result = combine(result, child.classify(rest));
+        }
+        for (pattern, child) in &self.wildcard_children {
+            if result == WalkAction::Skip {
+                break;
+            }
+            if pattern.matches(&component) {
+                result = combine(result, child.classify(rest));
+            }
+        }
+        result
+    }
+}
+
+/// A trie over the normalized keep set's path components, so classifying a
+/// walk entry costs O(depth of entry) rather than O(keep_size × depth).
+pub struct KeepTrie {
+    root: TrieNode,
+}
+
+impl KeepTrie {
+    pub fn build(keep: &BTreeSet<GlobPattern>) -> KeepTrie {
+        let mut root = TrieNode::default();
+        for pattern in keep {
+            root.insert(pattern.segments());
+        }
+        KeepTrie { root }
+    }
+
+    pub fn classify(&self, entry: &Path) -> WalkAction {
+        self.root
+            .classify(&entry.components().collect::<Vec<_>>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trie(patterns: &[&str]) -> KeepTrie {
+        KeepTrie::build(
+            &patterns
+                .iter()
+                .map(|p| GlobPattern::new(Path::new(p)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_classify_exact_and_ancestor() {
+        let trie = trie(&["/var/log"]);
+        assert_eq!(trie.classify(Path::new("/var/log")), WalkAction::Skip);
+        assert_eq!(trie.classify(Path::new("/var/log/journal")), WalkAction::Yield);
+        assert_eq!(trie.classify(Path::new("/var")), WalkAction::Recurse);
+    }
+
+    #[test]
+    fn test_classify_does_not_match_sibling_with_shared_prefix() {
+        // `/var/logaa` must not match `/var/log`: matching is per-component,
+        // never a byte-prefix comparison.
+        let log_trie = trie(&["/var/log"]);
+        assert_eq!(log_trie.classify(Path::new("/var/logaa")), WalkAction::Yield);
+
+        let logaa_trie = trie(&["/var/logaa"]);
+        assert_eq!(logaa_trie.classify(Path::new("/var/log")), WalkAction::Yield);
+    }
+
+    #[test]
+    fn test_classify_tries_every_matching_branch() {
+        // A literal keep entry and a wildcard keep entry can share a path
+        // prefix; an entry matching only the wildcard one must still be
+        // kept, not discarded because the literal branch didn't match.
+        let trie = trie(&["/a/b/x", "/a/*/y"]);
+        assert_eq!(trie.classify(Path::new("/a/b/x")), WalkAction::Skip);
+        assert_eq!(trie.classify(Path::new("/a/b/y")), WalkAction::Skip);
+        assert_eq!(trie.classify(Path::new("/a/b/z")), WalkAction::Yield);
+        assert_eq!(trie.classify(Path::new("/a/b")), WalkAction::Recurse);
+    }
+
+    #[test]
+    fn test_classify_wildcard_segment() {
+        let trie = trie(&["/etc/ssh/ssh_host_*_key"]);
+        assert_eq!(
+            trie.classify(Path::new("/etc/ssh/ssh_host_ed25519_key")),
+            WalkAction::Skip
+        );
+        assert_eq!(
+            trie.classify(Path::new("/etc/ssh/ssh_host_ed25519_key.pub")),
+            WalkAction::Yield
+        );
+        assert_eq!(trie.classify(Path::new("/etc/ssh")), WalkAction::Recurse);
+        assert_eq!(trie.classify(Path::new("/etc")), WalkAction::Recurse);
+    }
+
+    #[test]
+    fn test_classify_double_star_covers_everything_beneath() {
+        let trie = trie(&["/var/**"]);
+        assert_eq!(trie.classify(Path::new("/var/lib/nixos")), WalkAction::Skip);
+        // The path the `**` hangs off of is itself covered.
+        assert_eq!(trie.classify(Path::new("/var")), WalkAction::Skip);
+        assert_eq!(trie.classify(Path::new("/")), WalkAction::Recurse);
+        assert_eq!(trie.classify(Path::new("/etc")), WalkAction::Yield);
+    }
+}
@@ -1,76 +1,140 @@
 use std::collections::BTreeSet;
 use std::env;
-use std::fs;
-use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 use mountpoints::mountpaths;
-
-#[derive(Debug, PartialEq)]
-enum WalkAction {
-    Skip,
-    Recurse,
-    Yield,
+use rayon::prelude::*;
+use same_file::is_same_file;
+
+mod filesystem;
+mod glob_pattern;
+mod keep_trie;
+mod path_auditor;
+
+use filesystem::{DryRunFilesystem, Filesystem, StdFilesystem};
+use glob_pattern::GlobPattern;
+use keep_trie::{KeepTrie, WalkAction};
+use path_auditor::{AuditError, PathAuditor};
+
+fn walk(fs: &dyn Filesystem, auditor: &PathAuditor, root: &Path, keep: &KeepTrie) -> Vec<PathBuf> {
+    let entries = fs.read_dir(root).unwrap_or_default();
+
+    entries
+        .into_par_iter()
+        .filter(|entry_path| match auditor.audit(fs, entry_path) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("skipping {}: {:?}", entry_path.display(), e);
+                false
+            }
+        })
+        .flat_map(|entry_path| match keep.classify(&entry_path) {
+            WalkAction::Recurse => {
+                if fs
+                    .symlink_metadata(&entry_path)
+                    .map(|m| m.is_dir())
+                    .unwrap_or(false)
+                {
+                    walk(fs, auditor, &entry_path, keep)
+                } else {
+                    // A keep pattern matches something beneath this entry,
+                    // but it's a symlink (or otherwise not a real directory)
+                    // rather than something we can recurse into — moving it
+                    // whole would carry away the very thing `Recurse` is
+                    // meant to protect, so leave it alone instead.
+                    Vec::new()
+                }
+            }
+            WalkAction::Yield => vec![entry_path],
+            WalkAction::Skip => Vec::new(),
+        })
+        .collect()
 }
 
-fn walk_action(entry: &Path, keep: &BTreeSet<PathBuf>) -> WalkAction {
-    for path in keep {
-        if path == entry {
-            return WalkAction::Skip;
-        }
-        if path.starts_with(entry) {
-            return WalkAction::Recurse;
-        }
+/// Whether `a` and `b` refer to the same underlying inode, e.g. a bind
+/// mount or a symlink alias like `/var/run` -> `/run`. Only literal (glob-free)
+/// keep entries can be resolved this way, since `same_file` needs a real
+/// path to stat.
+fn same_file_alias(a: &GlobPattern, b: &GlobPattern) -> bool {
+    match (a.as_literal_path(), b.as_literal_path()) {
+        (Some(a), Some(b)) => is_same_file(&a, &b).unwrap_or(false),
+        _ => false,
     }
-    WalkAction::Yield
 }
 
-fn walk(root: &Path, keep: &BTreeSet<PathBuf>) -> Vec<PathBuf> {
-    let mut result = Vec::new();
-    if let Ok(entries) = fs::read_dir(root) {
-        for entry in entries.flatten() {
-            let entry_path = entry.path();
-            match walk_action(&entry_path, keep) {
-                WalkAction::Recurse => {
-                    if entry_path.is_dir() {
-                        result.extend(walk(&entry_path, keep));
-                    }
-                }
-                WalkAction::Yield => {
-                    result.push(entry_path);
-                }
-                WalkAction::Skip => continue,
-            }
+/// Resolve a keep entry that crosses a symlinked ancestor (e.g. `/var/run`
+/// aliasing `/run`) to its real path instead of rejecting it outright — the
+/// entry still needs to protect whatever it actually points at. Like
+/// `same_file_alias`, this goes straight to the real filesystem rather than
+/// through the `Filesystem` trait, since following a symlink chain isn't
+/// something that abstraction covers.
+fn resolve_symlinked_keep_entry(
+    joined: &Path,
+    canonical_root: &Path,
+    entry: &Path,
+) -> Option<GlobPattern> {
+    match std::fs::canonicalize(joined) {
+        Ok(canonical) if canonical.starts_with(canonical_root) => {
+            Some(GlobPattern::new(&canonical))
+        }
+        _ => {
+            eprintln!(
+                "ignoring unsafe keep entry {}: resolves outside root",
+                entry.display()
+            );
+            None
         }
     }
-    result
 }
 
 fn normalize_keep(
+    fs: &dyn Filesystem,
     root: &Path,
     target: &Path,
     mountpoints: Vec<PathBuf>,
     mut keep: BTreeSet<PathBuf>,
-) -> BTreeSet<PathBuf> {
+) -> BTreeSet<GlobPattern> {
     keep.insert(target.into());
 
+    let auditor = PathAuditor::new(root);
+    let canonical_root = std::fs::canonicalize(root).unwrap_or_else(|_| root.into());
+
     let mut keep = keep
         .iter()
-        .map(|entry| root.join(entry.strip_prefix("/").unwrap_or(entry)))
+        .filter_map(|entry| {
+            let joined = root.join(entry.strip_prefix("/").unwrap_or(entry));
+            match auditor.audit(fs, &joined) {
+                Ok(()) => Some(GlobPattern::new(&joined)),
+                Err(AuditError::SymlinkAncestor(_)) => {
+                    resolve_symlinked_keep_entry(&joined, &canonical_root, entry)
+                }
+                Err(e) => {
+                    eprintln!("ignoring unsafe keep entry {}: {:?}", entry.display(), e);
+                    None
+                }
+            }
+        })
         .collect::<BTreeSet<_>>();
 
     keep.extend(
         mountpoints
             .iter()
             .filter(|x| *x != root && x.starts_with(root))
-            .cloned(),
+            .map(|x| GlobPattern::new(x)),
     );
 
+    // A keep entry is redundant if another entry already covers it by path,
+    // or if the two are the same underlying inode (a bind mount or symlink
+    // alias) and `item2` sorts first, so exactly one survives per alias.
     keep.iter()
         .filter(|item1| {
-            !keep
-                .iter()
-                .any(|item2| *item1 != item2 && item1.starts_with(item2))
+            !keep.iter().any(|item2| {
+                *item1 != item2
+                    && (item1.covered_by(item2)
+                        || (same_file_alias(item1, item2) && item2 < item1))
+            })
         })
         .cloned()
         .collect()
@@ -84,7 +148,13 @@ fn target_path_to_root_path(root: &Path, target: &Path, path: &Path) -> Option<P
     path.strip_prefix(target).ok().map(|path| root.join(path))
 }
 
-fn create_target_parents(root: &Path, target: &Path, path: &Path) {
+fn create_target_parents(
+    fs: &dyn Filesystem,
+    root: &Path,
+    target: &Path,
+    path: &Path,
+    created: &Mutex<BTreeSet<PathBuf>>,
+) {
     for target_parent in root_path_to_target_path(root, target, path)
         .parent()
         .unwrap()
@@ -93,24 +163,39 @@ fn create_target_parents(root: &Path, target: &Path, path: &Path) {
         .iter()
         .rev()
     {
-        if !target_parent.exists() {
+        // Two worker threads can reach the same ancestor directory at the
+        // same time, so `created` also guards against a second thread
+        // racing us between the `exists()` check and `create()` below.
+        let mut created = created.lock().unwrap();
+        if created.contains(*target_parent) {
+            continue;
+        }
+
+        if !fs.exists(target_parent) {
             let parent =
                 target_path_to_root_path(root, target, target_parent).unwrap_or(root.into());
-            let parent_mode = parent
-                .metadata()
+            let parent_mode = fs
+                .metadata(&parent)
                 .ok()
-                .map(|x| x.permissions().mode())
+                .map(|x| x.mode)
                 .unwrap_or(0o700);
             println!(
                 "creating directory {} with mode {:#o}",
                 target_parent.display(),
                 parent_mode
             );
-            fs::DirBuilder::new()
-                .mode(parent_mode)
-                .create(target_parent)
-                .unwrap();
+            match fs.create_dir(target_parent, parent_mode) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => panic!(
+                    "failed to create directory {}: {:?}",
+                    target_parent.display(),
+                    e
+                ),
+            }
         }
+
+        created.insert(target_parent.to_path_buf());
     }
 }
 
@@ -119,55 +204,61 @@ fn path_file_name_to_number(path: &Path) -> Option<usize> {
         .and_then(|x| x.to_str().and_then(|x| x.parse::<usize>().ok()))
 }
 
-fn find_target_path_number(target_path: &Path) -> usize {
-    let number = fs::read_dir(target_path)
+fn find_target_path_number(fs: &dyn Filesystem, target_path: &Path) -> usize {
+    let number = fs
+        .read_dir(target_path)
         .ok()
-        .and_then(|entries| {
-            entries
-                .flatten()
-                .map(|entry| entry.path())
-                .filter_map(|x| path_file_name_to_number(&x))
-                .max()
-        })
+        .and_then(|entries| entries.iter().filter_map(|x| path_file_name_to_number(x)).max())
         .unwrap_or(0);
     number + 1
 }
 
-fn move_dirty(root: &Path, target: &Path, keep: &BTreeSet<PathBuf>) {
+fn move_dirty(fs: &dyn Filesystem, root: &Path, target: &Path, keep: &BTreeSet<GlobPattern>) {
     let target_path = root.join(target.strip_prefix("/").unwrap_or(target));
-    let target_path = target_path.join(format!("{:016}", find_target_path_number(&target_path)));
-
-    for path in walk(root, keep) {
-        create_target_parents(root, &target_path, &path);
+    let target_path =
+        target_path.join(format!("{:016}", find_target_path_number(fs, &target_path)));
+
+    let auditor = PathAuditor::new(root);
+    let created_parents: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+    let trie = KeepTrie::build(keep);
+
+    walk(fs, &auditor, root, &trie)
+        .into_par_iter()
+        .for_each(|path| {
+            if let Err(e) = auditor.audit(fs, &path) {
+                println!("refusing to move {}: {:?}", path.display(), e);
+                return;
+            }
 
-        let to = root_path_to_target_path(root, &target_path, &path);
-        if let Err(e) = fs::rename(&path, &to) {
-            println!(
-                "moving {} -> {} error! {:?}",
-                path.display(),
-                to.display(),
-                e
-            );
-        } else {
-            println!("moving {} -> {} ok!", path.display(), to.display());
-        }
-    }
+            create_target_parents(fs, root, &target_path, &path, &created_parents);
+
+            let to = root_path_to_target_path(root, &target_path, &path);
+            if let Err(e) = fs.rename(&path, &to) {
+                println!(
+                    "moving {} -> {} error! {:?}",
+                    path.display(),
+                    to.display(),
+                    e
+                );
+            } else {
+                println!("moving {} -> {} ok!", path.display(), to.display());
+            }
+        });
 }
 
-fn cleanup_old(root: &Path, target: &Path, retain: usize) {
+fn cleanup_old(fs: &dyn Filesystem, root: &Path, target: &Path, retain: usize) {
     if retain > 0 {
         let target_path = root.join(target.strip_prefix("/").unwrap_or(target));
-        if let Ok(entries) = fs::read_dir(target_path) {
+        if let Ok(entries) = fs.read_dir(&target_path) {
             let mut paths = entries
-                .flatten()
-                .map(|entry| entry.path())
-                .filter(|path| path.is_dir())
+                .into_iter()
+                .filter(|path| fs.metadata(path).map(|m| m.is_dir()).unwrap_or(false))
                 .collect::<Vec<_>>();
             if paths.len() > retain {
-                paths.sort_by_cached_key(|x| path_file_name_to_number(&x).unwrap_or(0));
+                paths.sort_by_cached_key(|x| path_file_name_to_number(x).unwrap_or(0));
                 for path in paths.iter().take(paths.len() - retain) {
                     println!("removing {}", path.display());
-                    fs::remove_dir_all(path).unwrap();
+                    fs.remove_dir_all(path).unwrap();
                 }
                 return;
             }
@@ -178,87 +269,101 @@ fn cleanup_old(root: &Path, target: &Path, retain: usize) {
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-
-    if args.len() < 5 {
-        eprintln!("Usage: {} <root> <target> <retain> <keep_json>", args[0]);
+    let dry_run = args.iter().any(|arg| arg == "--dry-run");
+    let positional: Vec<&String> = args
+        .iter()
+        .skip(1)
+        .filter(|arg| *arg != "--dry-run")
+        .collect();
+
+    if positional.len() < 4 {
+        eprintln!(
+            "Usage: {} [--dry-run] <root> <target> <retain> <keep_json>",
+            args[0]
+        );
         return;
     }
 
-    let root = Path::new(&args[1]);
-    let target = Path::new(&args[2]);
-    let retain = args[3].parse::<usize>().unwrap();
+    let root = Path::new(positional[0]);
+    let target = Path::new(positional[1]);
+    let retain = positional[2].parse::<usize>().unwrap();
+
+    let std_fs = StdFilesystem;
+    let dry_run_fs = DryRunFilesystem { inner: &std_fs };
+    let fs: &dyn Filesystem = if dry_run { &dry_run_fs } else { &std_fs };
 
     let keep = normalize_keep(
+        fs,
         root,
         target,
         mountpaths().unwrap(),
-        serde_json::from_str(&args[4]).unwrap(),
+        serde_json::from_str(positional[3]).unwrap(),
     );
 
-    move_dirty(root, target, &keep);
+    move_dirty(fs, root, target, &keep);
 
-    cleanup_old(root, target, retain);
+    cleanup_old(fs, root, target, retain);
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs;
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
     use tempfile::tempdir;
 
     use super::*;
+    use crate::filesystem::FakeFs;
 
     #[test]
     fn test_walk_action() {
-        assert_eq!(
-            walk_action(
-                Path::new("/var/log"),
-                &BTreeSet::from([Path::new("/var/log").into()]),
-            ),
-            WalkAction::Skip
-        );
+        let trie = KeepTrie::build(&BTreeSet::from([GlobPattern::new(Path::new("/var/log"))]));
+        assert_eq!(trie.classify(Path::new("/var/log")), WalkAction::Skip);
+        assert_eq!(trie.classify(Path::new("/var/logaa")), WalkAction::Yield);
 
-        assert_eq!(
-            walk_action(
-                Path::new("/var/log"),
-                &BTreeSet::from([Path::new("/var/log/journal").into()]),
-            ),
-            WalkAction::Recurse
-        );
+        let trie = KeepTrie::build(&BTreeSet::from([GlobPattern::new(Path::new(
+            "/var/log/journal",
+        ))]));
+        assert_eq!(trie.classify(Path::new("/var/log")), WalkAction::Recurse);
 
-        assert_eq!(
-            walk_action(
-                Path::new("/var/log"),
-                &BTreeSet::from([Path::new("/var/logaa").into()]),
-            ),
-            WalkAction::Yield
-        );
+        let trie = KeepTrie::build(&BTreeSet::from([GlobPattern::new(Path::new("/var/log"))]));
+        assert_eq!(trie.classify(Path::new("/var/logaa")), WalkAction::Yield);
 
+        let trie = KeepTrie::build(&BTreeSet::from([GlobPattern::new(Path::new(
+            "/etc/ssh/ssh_host_*_key",
+        ))]));
         assert_eq!(
-            walk_action(
-                Path::new("/var/logaa"),
-                &BTreeSet::from([Path::new("/var/log").into()]),
-            ),
-            WalkAction::Yield
+            trie.classify(Path::new("/etc/ssh/ssh_host_ed25519_key")),
+            WalkAction::Skip
         );
+        assert_eq!(trie.classify(Path::new("/etc/ssh")), WalkAction::Recurse);
+
+        let trie = KeepTrie::build(&BTreeSet::from([GlobPattern::new(Path::new("/var/**"))]));
+        assert_eq!(trie.classify(Path::new("/var/lib/nixos")), WalkAction::Skip);
     }
 
     #[test]
     fn test_normalize_keep() {
+        let std_fs = StdFilesystem;
+
         assert_eq!(
             normalize_keep(
+                &std_fs,
                 &Path::new("/"),
                 &Path::new("/oldroot"),
                 vec!["/".into(), "/run".into()],
                 BTreeSet::from([Path::new("/var/log").into(), Path::new("/var").into()])
             ),
             BTreeSet::from([
-                Path::new("/oldroot").into(),
-                Path::new("/run").into(),
-                Path::new("/var").into()
+                GlobPattern::new(Path::new("/oldroot")),
+                GlobPattern::new(Path::new("/run")),
+                GlobPattern::new(Path::new("/var")),
             ])
         );
 
         assert_eq!(
             normalize_keep(
+                &std_fs,
                 &Path::new("/sysroot"),
                 &Path::new("/oldroot"),
                 vec![
@@ -274,13 +379,198 @@ mod tests {
                 ])
             ),
             BTreeSet::from([
-                Path::new("/sysroot/oldroot").into(),
-                Path::new("/sysroot/run").into(),
-                Path::new("/sysroot/var").into()
+                GlobPattern::new(Path::new("/sysroot/oldroot")),
+                GlobPattern::new(Path::new("/sysroot/run")),
+                GlobPattern::new(Path::new("/sysroot/var")),
+            ])
+        );
+
+        // A glob keep entry is covered by a broader pattern the same way a
+        // literal path is covered by a literal ancestor.
+        assert_eq!(
+            normalize_keep(
+                &std_fs,
+                &Path::new("/"),
+                &Path::new("/oldroot"),
+                vec!["/".into()],
+                BTreeSet::from([
+                    Path::new("/var/lib/*/state").into(),
+                    Path::new("/var/lib/docker/state/sub").into(),
+                ])
+            ),
+            BTreeSet::from([
+                GlobPattern::new(Path::new("/oldroot")),
+                GlobPattern::new(Path::new("/var/lib/*/state")),
+            ])
+        );
+
+        // A keep entry containing `..` is rejected rather than silently
+        // resolved outside `root`.
+        assert_eq!(
+            normalize_keep(
+                &std_fs,
+                &Path::new("/sysroot"),
+                &Path::new("/oldroot"),
+                vec!["/".into()],
+                BTreeSet::from([Path::new("/var/../../etc").into()])
+            ),
+            BTreeSet::from([GlobPattern::new(Path::new("/sysroot/oldroot"))])
+        );
+    }
+
+    #[test]
+    fn test_normalize_keep_dedupes_same_file_aliases() {
+        let std_fs = StdFilesystem;
+        let tmpdir = tempdir().unwrap();
+        let root = tmpdir.path();
+
+        // `/run` is a symlink alias for `/persist/run`, the way `/var/run`
+        // aliases `/run` on a real NixOS system.
+        fs::DirBuilder::new()
+            .recursive(true)
+            .create(root.join("persist/run"))
+            .unwrap();
+        std::os::unix::fs::symlink(root.join("persist/run"), root.join("run")).unwrap();
+
+        let keep = normalize_keep(
+            &std_fs,
+            root,
+            Path::new("/oldroot"),
+            vec![],
+            BTreeSet::from([
+                Path::new("/run").into(),
+                Path::new("/persist/run").into(),
+            ]),
+        );
+
+        // Only one of the two aliases survives, alongside the target.
+        assert_eq!(
+            keep,
+            BTreeSet::from([
+                GlobPattern::new(&root.join("oldroot")),
+                GlobPattern::new(&root.join("persist/run")),
             ])
         );
     }
 
+    #[test]
+    fn test_normalize_keep_resolves_keep_entry_behind_symlinked_ancestor() {
+        let std_fs = StdFilesystem;
+        let tmpdir = tempdir().unwrap();
+        let root = tmpdir.path();
+
+        // `/var/run` aliases `/run` via a symlink, the way it does on a real
+        // NixOS system; a keep entry spelled through the alias must still
+        // protect the real directory it points at rather than being dropped.
+        fs::DirBuilder::new()
+            .recursive(true)
+            .create(root.join("run/dbus"))
+            .unwrap();
+        fs::DirBuilder::new().create(root.join("var")).unwrap();
+        std::os::unix::fs::symlink(root.join("run"), root.join("var/run")).unwrap();
+
+        let keep = normalize_keep(
+            &std_fs,
+            root,
+            Path::new("/oldroot"),
+            vec![],
+            BTreeSet::from([Path::new("/var/run/dbus").into()]),
+        );
+
+        let resolved_dbus = fs::canonicalize(root.join("run/dbus")).unwrap();
+        assert_eq!(
+            keep,
+            BTreeSet::from([
+                GlobPattern::new(&root.join("oldroot")),
+                GlobPattern::new(&resolved_dbus),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_move_dirty_does_not_move_symlink_with_kept_descendant() {
+        let fake = FakeFs::new();
+        fake.insert_dir("/var", 0o755);
+        fake.insert_symlink("/var/run");
+
+        let root = Path::new("/");
+        let target = Path::new("/oldroot");
+
+        // `FakeFs` entries don't exist on the real disk, so `normalize_keep`
+        // can't canonicalize a keep entry behind `/var/run`; build the keep
+        // set directly instead, with the entry spelled through the symlink.
+        let keep = BTreeSet::from([
+            GlobPattern::new(target),
+            GlobPattern::new(Path::new("/var/run/dbus")),
+        ]);
+
+        move_dirty(&fake, root, target, &keep);
+
+        // `/var/run` has a kept descendant, so it classifies as `Recurse`,
+        // but it's a symlink rather than a real directory — it must be left
+        // in place, not moved whole.
+        assert!(fake.exists(Path::new("/var/run")));
+    }
+
+    #[test]
+    fn test_move_dirty_with_fake_fs() {
+        let fake = FakeFs::new();
+        fake.insert_dir("/etc", 0o755);
+        fake.insert_dir("/etc/ssh", 0o700);
+        fake.insert_file("/etc/ssh/config", 0o644);
+        fake.insert_file("/etc/ssh/ssh_host_ed25519_key", 0o600);
+        fake.insert_dir("/var", 0o755);
+        fake.insert_dir("/var/log", 0o755);
+        fake.insert_file("/var/log/somelog", 0o644);
+
+        let root = Path::new("/");
+        let target = Path::new("/oldroot");
+        let keep = normalize_keep(
+            &fake,
+            root,
+            target,
+            vec![],
+            BTreeSet::from([Path::new("/etc/ssh/ssh_host_ed25519_key").into()]),
+        );
+
+        move_dirty(&fake, root, target, &keep);
+
+        let target_path_1 = Path::new("/oldroot/0000000000000001");
+
+        // `/var` has no kept descendants, so it's moved whole rather than
+        // recursed into.
+        assert!(!fake.exists(Path::new("/var")));
+        assert!(fake.exists(&target_path_1.join("var/log/somelog")));
+
+        // `/etc/ssh` is recursed into since it has a kept descendant, so its
+        // non-kept sibling is moved individually and the directory itself
+        // stays put.
+        assert!(fake.exists(Path::new("/etc/ssh")));
+        assert!(!fake.exists(Path::new("/etc/ssh/config")));
+        assert!(fake.exists(&target_path_1.join("etc/ssh/config")));
+
+        assert!(fake.exists(Path::new("/etc/ssh/ssh_host_ed25519_key")));
+        assert!(!fake.exists(&target_path_1.join("etc/ssh/ssh_host_ed25519_key")));
+    }
+
+    #[test]
+    fn test_move_dirty_dry_run_does_not_mutate() {
+        let fake = FakeFs::new();
+        fake.insert_dir("/var", 0o755);
+        fake.insert_file("/var/data", 0o644);
+
+        let root = Path::new("/");
+        let target = Path::new("/oldroot");
+        let keep = normalize_keep(&fake, root, target, vec![], BTreeSet::new());
+        let dry_run = DryRunFilesystem { inner: &fake };
+
+        move_dirty(&dry_run, root, target, &keep);
+
+        assert!(fake.exists(Path::new("/var")));
+        assert!(fake.exists(Path::new("/var/data")));
+        assert!(!fake.exists(Path::new("/oldroot")));
+    }
+
     #[test]
     fn test_create_target_parents() {
         let root_1 = Path::new("/");
@@ -386,6 +676,7 @@ mod tests {
 
     #[test]
     fn test_find_target_path_number() {
+        let std_fs = StdFilesystem;
         let tmpdir = tempdir().unwrap();
         let root = tmpdir.path();
         let target = Path::new("/oldroot");
@@ -397,7 +688,7 @@ mod tests {
         let target_path_3 = target_path.join(format!("{:016}", 3));
 
         assert_eq!(target_path_1.exists(), false);
-        assert_eq!(find_target_path_number(&target_path), 1);
+        assert_eq!(find_target_path_number(&std_fs, &target_path), 1);
 
         fs::DirBuilder::new()
             .recursive(true)
@@ -405,7 +696,7 @@ mod tests {
             .unwrap();
         assert_eq!(target_path_1.exists(), true);
         assert_eq!(target_path_2.exists(), false);
-        assert_eq!(find_target_path_number(&target_path), 2);
+        assert_eq!(find_target_path_number(&std_fs, &target_path), 2);
 
         fs::DirBuilder::new()
             .recursive(true)
@@ -413,7 +704,7 @@ mod tests {
             .unwrap();
 
         assert_eq!(target_path_unrelated.exists(), true);
-        assert_eq!(find_target_path_number(&target_path), 2);
+        assert_eq!(find_target_path_number(&std_fs, &target_path), 2);
 
         fs::DirBuilder::new()
             .recursive(true)
@@ -421,21 +712,23 @@ mod tests {
             .unwrap();
         assert_eq!(target_path_2.exists(), true);
 
-        cleanup_old(&root, &target, 1);
+        cleanup_old(&std_fs, &root, &target, 1);
         assert_eq!(target_path_1.exists(), false);
         assert_eq!(target_path_2.exists(), true);
         assert_eq!(target_path_3.exists(), false);
-        assert_eq!(find_target_path_number(&target_path), 3);
+        assert_eq!(find_target_path_number(&std_fs, &target_path), 3);
     }
 
     #[test]
     fn test_eyd() {
+        let std_fs = StdFilesystem;
         let tmpdir = tempdir().unwrap();
         let root = tmpdir.path();
         let target = Path::new("/oldroot");
         let target_path = root.join(target.strip_prefix("/").unwrap_or(&target));
         let mountpoints = vec![root.into(), root.join("run"), root.join("home")];
         let keep = normalize_keep(
+            &std_fs,
             root,
             target,
             mountpoints,
@@ -457,9 +750,9 @@ mod tests {
         let target_path_1 = target_path.join("0000000000000001");
         assert_eq!(target_path_1.exists(), false);
 
-        move_dirty(root, &target, &keep);
+        move_dirty(&std_fs, root, &target, &keep);
 
-        cleanup_old(root, target, 2);
+        cleanup_old(&std_fs, root, target, 2);
 
         assert_eq!(target_path_1.exists(), true);
         assert_eq!(root.join("etc/ssh").exists(), true);
@@ -517,9 +810,9 @@ mod tests {
         let target_path_2 = target_path.join("0000000000000002");
         assert_eq!(target_path_2.exists(), false);
 
-        move_dirty(root, &target, &keep);
+        move_dirty(&std_fs, root, &target, &keep);
 
-        cleanup_old(root, target, 2);
+        cleanup_old(&std_fs, root, target, 2);
 
         assert_eq!(target_path_1.exists(), true);
         assert_eq!(target_path_2.exists(), true);
@@ -549,9 +842,9 @@ mod tests {
         let target_path_3 = target_path.join("0000000000000003");
         assert_eq!(target_path_3.exists(), false);
 
-        move_dirty(root, &target, &keep);
+        move_dirty(&std_fs, root, &target, &keep);
 
-        cleanup_old(root, target, 2);
+        cleanup_old(&std_fs, root, target, 2);
 
         assert_eq!(target_path_1.exists(), false);
         assert_eq!(target_path_2.exists(), true);
@@ -568,7 +861,7 @@ mod tests {
         assert_eq!(target_path_unrelated.exists(), true);
         assert_eq!(fs::read_dir(&target_path).unwrap().count(), 3);
 
-        cleanup_old(root, target, 2);
+        cleanup_old(&std_fs, root, target, 2);
 
         assert_eq!(target_path_unrelated.exists(), false);
         assert_eq!(target_path_2.exists(), true);
@@ -0,0 +1,106 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::filesystem::{FileKind, Filesystem};
+
+/// Why [`PathAuditor::audit`] refused a path.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AuditError {
+    /// The path contains a literal `..` component.
+    ParentReference(PathBuf),
+    /// An ancestor directory of the path is a symlink, so we can't be sure
+    /// the path still resolves under `root`.
+    SymlinkAncestor(PathBuf),
+}
+
+/// Guards against a path escaping `root`, in the spirit of Mercurial's
+/// `path_auditor`: reject `..` components outright, and refuse to walk or
+/// move through a symlinked ancestor directory, since its target could
+/// point anywhere.
+pub struct PathAuditor {
+    root: PathBuf,
+}
+
+impl PathAuditor {
+    pub fn new(root: &Path) -> PathAuditor {
+        PathAuditor { root: root.into() }
+    }
+
+    /// `path` must already be rooted under `self.root` (e.g. via
+    /// `root.join(...)`). The final component of `path` itself is allowed
+    /// to be a symlink — callers move symlinks as leaf entries rather than
+    /// following them.
+    pub fn audit(&self, fs: &dyn Filesystem, path: &Path) -> Result<(), AuditError> {
+        if path.components().any(|c| c == Component::ParentDir) {
+            return Err(AuditError::ParentReference(path.into()));
+        }
+
+        let mut current = PathBuf::new();
+        let mut components = path.components().peekable();
+        while let Some(component) = components.next() {
+            current.push(component);
+            if components.peek().is_none() || !current.starts_with(&self.root) {
+                continue;
+            }
+            if let Ok(metadata) = fs.symlink_metadata(&current) {
+                if metadata.kind == FileKind::Symlink {
+                    return Err(AuditError::SymlinkAncestor(current));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::FakeFs;
+
+    #[test]
+    fn test_rejects_parent_reference() {
+        let auditor = PathAuditor::new(Path::new("/sysroot"));
+        let fake = FakeFs::new();
+        assert_eq!(
+            auditor.audit(&fake, Path::new("/sysroot/var/../../etc")),
+            Err(AuditError::ParentReference(
+                Path::new("/sysroot/var/../../etc").into()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_allows_plain_path() {
+        let auditor = PathAuditor::new(Path::new("/sysroot"));
+        let fake = FakeFs::new();
+        fake.insert_dir("/sysroot", 0o755);
+        fake.insert_dir("/sysroot/var", 0o755);
+        fake.insert_file("/sysroot/var/log", 0o644);
+
+        assert_eq!(auditor.audit(&fake, Path::new("/sysroot/var/log")), Ok(()));
+    }
+
+    #[test]
+    fn test_rejects_symlinked_ancestor() {
+        let auditor = PathAuditor::new(Path::new("/sysroot"));
+        let fake = FakeFs::new();
+        fake.insert_dir("/sysroot", 0o755);
+        fake.insert_symlink("/sysroot/var");
+        fake.insert_file("/sysroot/var/log", 0o644);
+
+        assert_eq!(
+            auditor.audit(&fake, Path::new("/sysroot/var/log")),
+            Err(AuditError::SymlinkAncestor(Path::new("/sysroot/var").into()))
+        );
+    }
+
+    #[test]
+    fn test_allows_symlinked_leaf() {
+        let auditor = PathAuditor::new(Path::new("/sysroot"));
+        let fake = FakeFs::new();
+        fake.insert_dir("/sysroot", 0o755);
+        fake.insert_symlink("/sysroot/var");
+
+        assert_eq!(auditor.audit(&fake, Path::new("/sysroot/var")), Ok(()));
+    }
+}